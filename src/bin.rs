@@ -49,7 +49,7 @@ fn main() {
 
     let filename = matches.value_of("file").unwrap(); // Safe bc required
 
-    let gbx = parse_from_file(filename);
+    let gbx = parse_from_file(filename, ParseOptions::default());
     if let Err(p) = gbx {
         println!("{}", error_style.paint(format!("{:?}", p)));
         return;
@@ -0,0 +1,207 @@
+//! Low-level byte reading for the GBX body: a cursor over a byte slice plus GBX's "lookback
+//! string" decoding scheme, which is how every string in the body (class names, filenames, ...)
+//! is encoded.
+
+use std::convert::TryInto;
+
+use super::parser::ParseError;
+
+/// Cursor over a GBX byte buffer.
+///
+/// Beyond plain primitive reads, it tracks the per-chunk lookback-string state: the first
+/// lookback string read after construction (or after [reset_lookback](GbxReader::reset_lookback))
+/// consumes a version word, and every string read after that can cheaply reference one seen
+/// earlier in the same chunk instead of repeating its bytes.
+pub struct GbxReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    lookback_version_read: bool,
+    lookback_strings: Vec<String>,
+}
+
+impl<'a> GbxReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        GbxReader {
+            buf,
+            pos: 0,
+            lookback_version_read: false,
+            lookback_strings: Vec::new(),
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .ok_or(ParseError::FileTooShort)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, ParseError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        self.take(len)
+    }
+
+    /// Reads a plain, length-prefixed (`u32` byte length) UTF-8 string. Most strings in the body
+    /// are lookback strings instead; see [read_lookback_string](GbxReader::read_lookback_string).
+    pub fn read_string(&mut self) -> Result<String, ParseError> {
+        let len = self.read_u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ParseError::InvalidUtf8)
+    }
+
+    /// Resets the lookback-string state. Must be called when moving to a new chunk: the scheme
+    /// (including the string table and whether the version word has been read yet) is scoped per
+    /// chunk, not per file.
+    pub fn reset_lookback(&mut self) {
+        self.lookback_version_read = false;
+        self.lookback_strings.clear();
+    }
+
+    /// Reads a GBX "lookback string".
+    ///
+    /// On the first call since construction or the last [reset_lookback](GbxReader::reset_lookback),
+    /// a `u32` version word is read and checked to equal `3`. Every call then reads a `u32` index:
+    /// `0xFFFFFFFF` is the empty string; if the top two flag bits are both clear, the index names
+    /// a predefined collection-name constant this crate doesn't resolve; otherwise, if the lower
+    /// 30 bits are zero, a length-prefixed UTF-8 string follows and is appended to this reader's
+    /// string table; otherwise the lower 30 bits are a 1-based index into the table of strings
+    /// seen so far in this chunk.
+    pub fn read_lookback_string(&mut self) -> Result<String, ParseError> {
+        if !self.lookback_version_read {
+            let version = self.read_u32()?;
+            if version != 3 {
+                return Err(ParseError::InvalidLookbackVersion(version));
+            }
+            self.lookback_version_read = true;
+        }
+
+        const FRESH_STRING_FLAGS: u32 = 0xC000_0000;
+        const INDEX_MASK: u32 = 0x3FFF_FFFF;
+
+        let index = self.read_u32()?;
+        if index == 0xFFFF_FFFF {
+            return Ok(String::new());
+        }
+
+        if index & FRESH_STRING_FLAGS == 0 {
+            // A predefined collection-name constant (e.g. "Stadium", "Canyon"); this crate only
+            // models the handful of collections [Environment](crate::gbx::Environment) knows
+            // about, so the raw index is surfaced instead of a resolved name. The flag bits are
+            // clear here, so the whole index is the collection id.
+            return Ok(format!("#collection:{}", index & INDEX_MASK));
+        }
+
+        let body = index & INDEX_MASK;
+        if body == 0 {
+            let s = self.read_string()?;
+            self.lookback_strings.push(s.clone());
+            Ok(s)
+        } else {
+            let table_index = (body - 1) as usize;
+            self.lookback_strings
+                .get(table_index)
+                .cloned()
+                .ok_or(ParseError::InvalidLookbackIndex(index))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version_word() -> [u8; 4] {
+        3u32.to_le_bytes()
+    }
+
+    #[test]
+    fn read_lookback_string_returns_empty_string_for_the_sentinel_index() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&version_word());
+        buf.extend_from_slice(&0xFFFF_FFFFu32.to_le_bytes());
+
+        let mut reader = GbxReader::new(&buf);
+        assert_eq!(reader.read_lookback_string().unwrap(), "");
+    }
+
+    #[test]
+    fn read_lookback_string_reads_a_fresh_string_flagged_with_only_the_top_bit_set() {
+        // GBX marks fresh strings with the top two bits, not just bit 31; an index with only bit
+        // 31 set must still be treated as fresh rather than as a collection-name constant.
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&version_word());
+        buf.extend_from_slice(&0x8000_0000u32.to_le_bytes());
+        buf.extend_from_slice(&4u32.to_le_bytes());
+        buf.extend_from_slice(b"Test");
+
+        let mut reader = GbxReader::new(&buf);
+        assert_eq!(reader.read_lookback_string().unwrap(), "Test");
+    }
+
+    #[test]
+    fn read_lookback_string_resolves_a_back_reference_into_the_string_table() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&version_word());
+        buf.extend_from_slice(&0xC000_0000u32.to_le_bytes());
+        buf.extend_from_slice(&4u32.to_le_bytes());
+        buf.extend_from_slice(b"Test");
+        // A back-reference still carries the flag bits; only the low 30 bits are the 1-based
+        // table index.
+        buf.extend_from_slice(&0xC000_0001u32.to_le_bytes());
+
+        let mut reader = GbxReader::new(&buf);
+        assert_eq!(reader.read_lookback_string().unwrap(), "Test");
+        assert_eq!(reader.read_lookback_string().unwrap(), "Test");
+    }
+
+    #[test]
+    fn read_lookback_string_surfaces_unresolved_collection_constants() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&version_word());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+
+        let mut reader = GbxReader::new(&buf);
+        assert_eq!(reader.read_lookback_string().unwrap(), "#collection:0");
+    }
+
+    #[test]
+    fn read_lookback_string_surfaces_a_collection_constant_with_nonzero_low_bits() {
+        // The flag bits are clear but the low bits aren't zero, e.g. a real "Stadium" collection
+        // id (26); this must not be misread as a 1-based back-reference into the string table.
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&version_word());
+        buf.extend_from_slice(&26u32.to_le_bytes());
+
+        let mut reader = GbxReader::new(&buf);
+        assert_eq!(reader.read_lookback_string().unwrap(), "#collection:26");
+    }
+
+    #[test]
+    fn read_lookback_string_rejects_an_unexpected_version_word() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&2u32.to_le_bytes());
+
+        let mut reader = GbxReader::new(&buf);
+        assert!(matches!(
+            reader.read_lookback_string(),
+            Err(ParseError::InvalidLookbackVersion(2))
+        ));
+    }
+}
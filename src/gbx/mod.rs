@@ -1,3 +1,4 @@
+pub mod binary;
 pub mod parser;
 
 use fmt::{Debug, Display};
@@ -6,6 +7,8 @@ use std::{convert::TryFrom, fmt};
 
 use enum_repr::EnumRepr;
 
+pub use parser::body::ReferenceTable;
+
 /// Container for any data extracted from a GBX file.
 ///
 /// See [parse_from_buffer](parser::parse_from_buffer).
@@ -17,8 +20,26 @@ pub struct GBX {
     header_start: usize,
     header_length: usize,
     pub bin_header: GBXBinaryHeader,
-    pub replay_header: Option<ReplayXMLHeader>,
+    /// The parsed `<header>` block, whichever variant the `type` attribute selected.
+    pub header: Option<GBXHeader>,
     pub header_xml: String,
+    /// Counts from the node reference table, present only when [body](GBX::body) was decoded too.
+    pub reference_table: Option<ReferenceTable>,
+    /// The decoded (and, if necessary, LZO-decompressed) node data following the header.
+    ///
+    /// `None` if the body could not be located or, without the `lzo` feature enabled, if it was
+    /// LZO-compressed.
+    pub body: Option<Vec<u8>>,
+    /// Everything read before `<header `, kept verbatim for [writer](parser::writer).
+    pub(crate) raw_prefix: Vec<u8>,
+    /// Everything read after `</header>`, kept verbatim for [writer](parser::writer).
+    pub(crate) raw_suffix: Vec<u8>,
+    /// Absolute offset within `raw_prefix` of the header-chunk descriptor table's size field for
+    /// the chunk that carries the XML header, if the table could be located.
+    ///
+    /// [writer](parser::writer) uses this to keep that field, and `userDataSize`, in sync when a
+    /// regenerated header XML differs in byte length from the one that was parsed.
+    pub(crate) xml_chunk_size_offset: Option<usize>,
 }
 
 impl Display for GBX {
@@ -29,11 +50,11 @@ impl Display for GBX {
         }
         write!(
             f,
-            "GBX Info Dump (Size={}B)\nFrom file={}\n\nMagic\n=====\n{}\n\nReplay\n======\n{}",
+            "GBX Info Dump (Size={}B)\nFrom file={}\n\nMagic\n=====\n{}\n\nHeader\n======\n{}",
             self.filesize,
             self.origin,
             self.bin_header,
-            unoption(&self.replay_header.as_ref())
+            unoption(&self.header.as_ref())
         )
     }
 }
@@ -73,6 +94,9 @@ impl Display for GBXOrigin {
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct GBXBinaryHeader {
     pub version: u16,
+    pub format: GBXFormat,
+    pub ref_table_compression: Compression,
+    pub body_compression: Compression,
     pub class_id: u32,
 }
 
@@ -80,14 +104,42 @@ impl Display for GBXBinaryHeader {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "v{}, class id: {:08x} ({:?})",
+            "v{}, format: {:?}, body: {:?}, class id: {:08x} ({:?})",
             self.version,
+            self.format,
+            self.body_compression,
             self.class_id,
             MapClass::try_from(self.class_id).map_or("unknown".to_owned(), |c| format!("{:?}", c))
         )
     }
 }
 
+/// Whether a GBX file's body is stored as binary data (the common case) or as text.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum GBXFormat {
+    Binary,
+    Text,
+}
+
+impl Default for GBXFormat {
+    fn default() -> Self {
+        GBXFormat::Binary
+    }
+}
+
+/// Compression marker for the reference table / body sections of a GBX file.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Compression {
+    Compressed,
+    Uncompressed,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Compressed
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub struct ReplayXMLHeader {
     /// Version of the replay file format
@@ -132,12 +184,117 @@ impl Display for ReplayScore {
     }
 }
 
+/// A parsed `<header>` block, tagged by the `type` attribute that selected which variant to parse.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum GBXHeader {
+    Replay(ReplayXMLHeader),
+    Challenge(ChallengeXMLHeader),
+}
+
+impl Display for GBXHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GBXHeader::Replay(h) => write!(f, "{}", h),
+            GBXHeader::Challenge(h) => write!(f, "{}", h),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct ChallengeXMLHeader {
+    pub maptype: MapType,
+    /// Version of the challenge file format
+    pub mapversion: GBXVersion,
+    /// Version on executable used to build the map
+    pub exever: String,
+    /// UID of Map
+    pub uid: String,
+    /// Name of Map
+    pub name: String,
+    /// Login of the map's author
+    pub author: String,
+    pub envir: Environment,
+    pub mood: Mood,
+    pub desctype: DescType,
+    pub nblaps: u32,
+    pub price: u32,
+    /// `<desc>`'s `nbcheckpoints` attribute, carried through unmodeled.
+    pub nbcheckpoints: Option<u32>,
+    /// `<desc>`'s `displaycost` attribute, carried through unmodeled.
+    pub displaycost: Option<u32>,
+    /// `<desc>`'s `mod` attribute, carried through unmodeled.
+    pub map_mod: Option<String>,
+    /// `<ident>`'s `authorzone` attribute, carried through unmodeled.
+    pub authorzone: Option<String>,
+    pub playermodel: Option<PlayerModel>,
+    pub times: Option<Times>,
+    pub dependencies: Vec<Dependency>,
+}
+
+impl Display for ChallengeXMLHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn unoption<T: Display>(o: &Option<&T>) -> String {
+            o.map(|x| format!("{}", x))
+                .unwrap_or_else(|| "Not present".to_owned())
+        }
+        write!(
+            f,
+            "Version: {:?}\nExever.: {}\nMap: {} ({})\nAuthor: {}\nEnvironment: {:?}, Mood: {:?}\nTimes: {}",
+            self.mapversion,
+            self.exever,
+            self.name,
+            self.uid,
+            self.author,
+            self.envir,
+            self.mood,
+            unoption(&self.times.as_ref())
+        )
+    }
+}
+
+/// Medal times and author score/time for a challenge, in milliseconds. Absent (`-1` in the XML)
+/// when the map has no medal set for that field.
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct Times {
+    pub bronze: Option<u32>,
+    pub silver: Option<u32>,
+    pub gold: Option<u32>,
+    pub authortime: Option<u32>,
+    pub authorscore: Option<u32>,
+}
+
+impl Display for Times {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn unoption(o: &Option<u32>) -> String {
+            o.map(|x| x.to_string()).unwrap_or_else(|| "-".to_owned())
+        }
+        write!(
+            f,
+            "bronze={}, silver={}, gold={}, author={} ({} pts)",
+            unoption(&self.bronze),
+            unoption(&self.silver),
+            unoption(&self.gold),
+            unoption(&self.authortime),
+            unoption(&self.authorscore)
+        )
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Dependency {
     pub file: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The `<playermodel>` element's attributes. This crate doesn't interpret them, only carries them
+/// through so a round-tripped file keeps its custom player model.
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct PlayerModel {
+    pub id: String,
+    pub name: String,
+    pub collection: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum MapType {
     Challenge,
 }
@@ -209,6 +366,10 @@ impl Default for GBXVersion {
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Environment {
     Stadium,
+    /// An environment this crate doesn't model yet, holding the raw `envir` attribute.
+    ///
+    /// Only ever produced when parsing with [FailedResolveStrategy::Stub](parser::FailedResolveStrategy::Stub).
+    Other(String),
 }
 
 impl TryFrom<&str> for Environment {
@@ -1,30 +1,145 @@
 //! Package containing the parser for GBX Files.
 //! The datatypes used are defined in the [gbx](crate::gbx) module, with [GBX](crate::gbx::GBX) being the main one.
 
+pub mod body;
+pub mod challenge;
+pub mod events;
 pub mod replay;
+pub mod writer;
 
+use self::body::parse_body;
+use self::challenge::parse_challenge_header_xml;
 use self::replay::parse_replay_xml;
 
 use super::*;
 
 use std::convert::TryInto;
 use std::io;
-use std::io::Read;
+use std::io::{Cursor, Read};
 use std::{fs::File, num::ParseIntError};
 
+use flate2::read::GzDecoder;
+use thiserror::Error;
+use xml::common::TextPosition;
+use xml::{reader::XmlEvent, EventReader};
+
 const HEADER_START_TOKEN: &[u8] = "<header ".as_bytes();
 const HEADER_END_TOKEN: &[u8] = "</header>".as_bytes();
 
-#[derive(Debug)]
+/// The two leading bytes of any gzip stream, per RFC 1952.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Length of the fixed magic/version/compression/class_id prefix; the user-data section (the
+/// header-chunk table, the XML header chunk's bytes, and any other header chunks) starts right
+/// after it.
+const BINARY_HEADER_LEN: usize = 13;
+
+/// Set on a header chunk's descriptor-table size field to mark it as a "heavy" chunk; the actual
+/// byte size is the remaining bits.
+const HEAVY_CHUNK_FLAG: u32 = 0x8000_0000;
+
+/// Controls how the parser reacts to a value it doesn't recognize (an unknown [Environment],
+/// [Mood], [GBXVersion], or similar enum read from the XML header).
+///
+/// GBX is a moving target: every TrackMania update can introduce new environments or class IDs,
+/// and this crate shouldn't need a release before it can parse a file that merely mentions one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailedResolveStrategy {
+    /// Fail the parse with [ParseError::HeaderTryIntoEnumError], today's behavior.
+    Error,
+    /// Skip the attribute and leave the field at its default.
+    Ignore,
+    /// Record the raw value on a stub carrier (e.g. `Environment::Other`) where one exists,
+    /// otherwise behave like [Ignore](FailedResolveStrategy::Ignore).
+    Stub,
+}
+
+impl Default for FailedResolveStrategy {
+    fn default() -> Self {
+        FailedResolveStrategy::Error
+    }
+}
+
+/// Options controlling [parse_from_buffer]/[parse_from_file] and the XML header parsers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    pub resolve: FailedResolveStrategy,
+}
+
+/// Applies a [FailedResolveStrategy] to the outcome of converting a raw XML attribute value into
+/// a typed enum. Shared by the replay and challenge header parsers.
+pub(crate) fn resolve_enum<T>(
+    result: Result<T, String>,
+    resolve: FailedResolveStrategy,
+    stub: impl FnOnce(String) -> T,
+    attribute: &str,
+    position: TextPosition,
+) -> Result<Option<T>, ParseError> {
+    match result {
+        Ok(v) => Ok(Some(v)),
+        Err(value) => match resolve {
+            FailedResolveStrategy::Error => Err(ParseError::HeaderTryIntoEnumError {
+                value,
+                attribute: attribute.to_owned(),
+                position,
+            }),
+            FailedResolveStrategy::Ignore => Ok(None),
+            FailedResolveStrategy::Stub => Ok(Some(stub(value))),
+        },
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum ParseError {
+    #[error("buffer is missing the \"GBX\" magic bytes")]
     MissingGBXMagic,
+    #[error("buffer ended before the fixed-size GBX header or a field read from the body")]
     FileTooShort,
+    /// The header buffer was too short to even sniff for the gzip magic bytes.
+    #[error("buffer is shorter than 2 bytes, can't sniff for gzip compression")]
+    InputTooShort,
+    #[error("no <header> element found")]
     HeaderNotFound,
+    #[error("no embedded thumbnail found")]
     ThumbnailNotFound,
-    XMLParseError(xml::reader::Error),
-    HeaderValueError(ParseIntError),
-    HeaderTryIntoEnumError(String),
-    IOError(io::Error),
+    #[error("failed to parse header XML")]
+    XMLParseError(#[source] xml::reader::Error),
+    #[error("invalid value for `{attribute}` at {position}")]
+    HeaderValueError {
+        #[source]
+        source: ParseIntError,
+        attribute: String,
+        position: TextPosition,
+    },
+    #[error("invalid value \"{value}\" for `{attribute}` at {position}")]
+    HeaderTryIntoEnumError {
+        value: String,
+        attribute: String,
+        position: TextPosition,
+    },
+    #[error("I/O error")]
+    IOError(#[source] io::Error),
+    /// LZO decompression of the body failed.
+    #[error("failed to LZO-decompress the body")]
+    DecompressionError,
+    /// The body was LZO-compressed but this crate was built without the `lzo` feature.
+    #[error("body is LZO-compressed but this crate was built without the `lzo` feature")]
+    CompressionFeatureDisabled,
+    /// A lookback string's version word wasn't the expected value of `3`.
+    #[error("lookback string version {0} is not the expected 3")]
+    InvalidLookbackVersion(u32),
+    /// A lookback string's index didn't name any previously-read string.
+    #[error("lookback string index {0} does not name a previously-read string")]
+    InvalidLookbackIndex(u32),
+    /// A string read from the body was not valid UTF-8.
+    #[error("string was not valid UTF-8")]
+    InvalidUtf8,
+    /// The reference table named external nodes, whose entries (a recursive ancestor-folder
+    /// section plus a variable-sized record per node) this crate does not model.
+    #[error("body references {0} external node(s), which this crate cannot parse past")]
+    ExternalNodesUnsupported(u32),
+    #[error("unknown parse error")]
     Unknown,
 }
 
@@ -33,14 +148,129 @@ fn find_window(buf: &[u8], needle: &[u8]) -> Option<usize> {
     buf.windows(needle.len()).position(|w| w == needle)
 }
 
+/// Parses the fixed-size GBX magic/version/compression prefix, shared by the buffer and reader
+/// entry points.
+fn parse_binary_header(buffer: &[u8]) -> Result<GBXBinaryHeader, ParseError> {
+    if buffer.len() < BINARY_HEADER_LEN {
+        return Err(ParseError::FileTooShort);
+    }
+
+    if &buffer[0..3] != b"GBX" {
+        return Err(ParseError::MissingGBXMagic);
+    }
+
+    Ok(GBXBinaryHeader {
+        version: u16::from_le_bytes((&buffer[3..5]).try_into().unwrap()),
+        format: match buffer[5] {
+            b'T' => GBXFormat::Text,
+            _ => GBXFormat::Binary,
+        },
+        ref_table_compression: match buffer[6] {
+            b'U' => Compression::Uncompressed,
+            _ => Compression::Compressed,
+        },
+        body_compression: match buffer[7] {
+            b'U' => Compression::Uncompressed,
+            _ => Compression::Compressed,
+        },
+        class_id: u32::from_le_bytes((&buffer[9..13]).try_into().unwrap()),
+    })
+}
+
+/// Finds the absolute offset of the size field belonging to the header chunk that `xml_start`
+/// (the offset of `<header ` within `buffer`) falls inside, by walking the header-chunk
+/// descriptor table that follows `userDataSize`.
+///
+/// Used by [writer](super::parser::writer) to keep that field (and, transitively,
+/// `userDataSize`) in sync when a caller writes back a regenerated XML header of a different
+/// length than the one that was parsed. Returns `None` rather than erroring if the table doesn't
+/// look the way this crate expects, the same best-effort spirit as [parse_body](body::parse_body).
+fn locate_xml_chunk_size_offset(buffer: &[u8], xml_start: usize) -> Option<usize> {
+    let num_chunks_offset = BINARY_HEADER_LEN + 4;
+    let num_chunks = u32::from_le_bytes(
+        buffer
+            .get(num_chunks_offset..num_chunks_offset + 4)?
+            .try_into()
+            .ok()?,
+    ) as usize;
+
+    let table_start = num_chunks_offset + 4;
+    let mut chunk_data_offset = table_start + num_chunks * 8;
+
+    for i in 0..num_chunks {
+        let size_field_offset = table_start + i * 8 + 4;
+        let size_raw = u32::from_le_bytes(
+            buffer
+                .get(size_field_offset..size_field_offset + 4)?
+                .try_into()
+                .ok()?,
+        );
+        let size = (size_raw & !HEAVY_CHUNK_FLAG) as usize;
+
+        if (chunk_data_offset..chunk_data_offset + size).contains(&xml_start) {
+            return Some(size_field_offset);
+        }
+        chunk_data_offset += size;
+    }
+
+    None
+}
+
+/// Reads the `type` attribute off the `<header>` start element, without building either typed
+/// header struct, so [parse_header_xml](parse_header_xml) knows which one to build.
+///
+/// Also returns the position of the `<header>` element, so a caller that doesn't recognize the
+/// `type` it finds there can point at exactly where.
+fn peek_header_type(buf: &[u8]) -> Result<(String, TextPosition), ParseError> {
+    let mut xmlp = EventReader::new(buf);
+    loop {
+        let position = xmlp.position();
+        match xmlp.next() {
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) => {
+                if name.local_name == "header" {
+                    return attributes
+                        .into_iter()
+                        .find(|attr| attr.name.local_name == "type")
+                        .map(|attr| (attr.value, position))
+                        .ok_or(ParseError::HeaderNotFound);
+                }
+            }
+            Ok(XmlEvent::EndDocument) => return Err(ParseError::HeaderNotFound),
+            Ok(_) => (),
+            Err(e) => return Err(ParseError::XMLParseError(e)),
+        }
+    }
+}
+
+/// Parses a GBX `<header>` block, dispatching on its `type` attribute the way an m3u8 parser
+/// distinguishes a master playlist from a media one by the tags it opens with.
+///
+/// Replay files (`type="replay"`) and challenge/map files (`type="challenge"`) carry entirely
+/// different child elements, so each gets its own typed struct; both are wrapped in [GBXHeader]
+/// so callers don't need to know up front which one a given file contains.
+pub(crate) fn parse_header_xml(buf: &[u8], options: ParseOptions) -> Result<GBXHeader, ParseError> {
+    let (header_type, position) = peek_header_type(buf)?;
+    match header_type.to_lowercase().as_str() {
+        "replay" => parse_replay_xml(buf, options).map(GBXHeader::Replay),
+        "challenge" => parse_challenge_header_xml(buf, options).map(GBXHeader::Challenge),
+        _ => Err(ParseError::HeaderTryIntoEnumError {
+            value: header_type,
+            attribute: "type".to_owned(),
+            position,
+        }),
+    }
+}
+
 /// Reads the contents from `filename` and parses them identically to [parse_from_buffer](parse_from_buffer).
 ///
 /// Note, that the [GBXOrigin](GBXOrigin) of the returned [GBX](GBX) will be `File{path:<filepath>}`.
-pub fn parse_from_file(filename: &str) -> Result<GBX, ParseError> {
+pub fn parse_from_file(filename: &str, options: ParseOptions) -> Result<GBX, ParseError> {
     let mut buffer = Vec::new();
     let mut f = File::open(filename).map_err(ParseError::IOError)?;
     f.read_to_end(&mut buffer).map_err(ParseError::IOError)?;
-    let mut gbx = parse_from_buffer(&buffer)?;
+    let mut gbx = parse_from_buffer(&buffer, options)?;
     gbx.origin = GBXOrigin::File {
         path: String::from(filename),
     };
@@ -49,39 +279,64 @@ pub fn parse_from_file(filename: &str) -> Result<GBX, ParseError> {
 
 /// Parses the given slice of bytes as if it was a GBX file.
 ///
+/// `buffer` is sniffed for the gzip magic bytes first and transparently decompressed if present,
+/// so callers can hand this either a raw GBX file or a gzip-compressed one, the way librsvg's
+/// loader accepts both `svg` and `svgz` through the same entry point.
+///
 /// This function assumes the XML header included in the GBX file is valid UTF8, and will panic
 /// otherwise.
-/// As of now the actual map-data is not extracted.
+/// The reference table and body are decoded on a best-effort basis; see
+/// [GBX::reference_table](GBX::reference_table) and [GBX::body](GBX::body).
 ///
 /// If you want to parse a file directly see [parse_from_file](parse_from_file).
-pub fn parse_from_buffer(buffer: &[u8]) -> Result<GBX, ParseError> {
-    if buffer.len() < 3 {
-        return Err(ParseError::FileTooShort);
+pub fn parse_from_buffer(buffer: &[u8], options: ParseOptions) -> Result<GBX, ParseError> {
+    if buffer.len() < 2 {
+        return Err(ParseError::InputTooShort);
     }
 
-    if &buffer[0..3] != b"GBX" {
-        return Err(ParseError::MissingGBXMagic);
-    }
-
-    let binary_header = GBXBinaryHeader {
-        version: u16::from_le_bytes((&buffer[3..5]).try_into().unwrap()),
-        class_id: u32::from_le_bytes((&buffer[9..13]).try_into().unwrap()),
+    let decompressed;
+    let buffer = if buffer[..2] == GZIP_MAGIC {
+        let mut out = Vec::new();
+        GzDecoder::new(buffer)
+            .read_to_end(&mut out)
+            .map_err(ParseError::IOError)?;
+        decompressed = out;
+        decompressed.as_slice()
+    } else {
+        buffer
     };
 
+    let binary_header = parse_binary_header(buffer)?;
+
     let header_start = find_window(buffer, HEADER_START_TOKEN).ok_or(ParseError::HeaderNotFound);
     let header_end = find_window(buffer, HEADER_END_TOKEN)
         .ok_or(ParseError::HeaderNotFound)
         .map(|x| x + HEADER_END_TOKEN.len());
 
     let mut header_xml = Vec::new();
-    let mut replay_header = Err(ParseError::HeaderNotFound);
+    let mut header = Err(ParseError::HeaderNotFound);
 
     let hs = *header_start.as_ref().unwrap_or(&0);
     let he = *header_end.as_ref().unwrap_or(&0);
 
+    let mut reference_table = None;
+    let mut body = None;
+    let mut xml_chunk_size_offset = None;
+
     if header_start.is_ok() && header_end.is_ok() {
         header_xml.extend_from_slice(&buffer[hs..he]);
-        replay_header = parse_replay_xml(&buffer[hs..he]);
+        header = parse_header_xml(&buffer[hs..he], options);
+        xml_chunk_size_offset = locate_xml_chunk_size_offset(buffer, hs);
+
+        // Body parsing is best-effort: it relies on header-chunk/reference-table layout this
+        // crate infers rather than fully models, so a failure here shouldn't take down a caller
+        // that only wanted the XML header. The user-data section (and thus the reference table
+        // that follows it) starts right after the fixed prefix, not after the XML chunk the
+        // header happens to be found at.
+        if let Ok((rt, b)) = parse_body(&buffer[BINARY_HEADER_LEN..], &binary_header) {
+            reference_table = Some(rt);
+            body = Some(b);
+        }
     }
     let header_xml = String::from_utf8(header_xml).unwrap();
 
@@ -90,8 +345,159 @@ pub fn parse_from_buffer(buffer: &[u8]) -> Result<GBX, ParseError> {
         filesize: buffer.len(),
         header_length: he - hs,
         header_start: hs,
-        replay_header: replay_header.ok(),
+        header: header.ok(),
         header_xml,
         bin_header: binary_header,
+        reference_table,
+        body,
+        raw_prefix: buffer[..hs].to_vec(),
+        raw_suffix: buffer[he..].to_vec(),
+        xml_chunk_size_offset,
     })
 }
+
+/// Scans `r` for the first occurrence of `needle`, appending bytes read along the way to
+/// `buffer`, and returns the offset of the match within `buffer`.
+///
+/// Each pass scans everything appended since the last pass, plus `needle.len() - 1` bytes of
+/// overlap (a match can't start any earlier than that without having already been found), so the
+/// whole accumulated buffer is never rescanned from the start of the *function*, though the
+/// buffer handed in from a previous call is rescanned once.
+fn read_until_window<R: Read>(
+    r: &mut R,
+    needle: &[u8],
+    buffer: &mut Vec<u8>,
+) -> Result<usize, ParseError> {
+    let mut scanned = 0usize;
+    let mut chunk = [0u8; 4096];
+    loop {
+        let scan_from = scanned.saturating_sub(needle.len().saturating_sub(1));
+        if let Some(pos) = find_window(&buffer[scan_from..], needle) {
+            return Ok(scan_from + pos);
+        }
+        scanned = buffer.len();
+
+        let read = r.read(&mut chunk).map_err(ParseError::IOError)?;
+        if read == 0 {
+            return Err(ParseError::HeaderNotFound);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// Parses a GBX file from any [Read] source, stopping as soon as `</header>` has been seen
+/// instead of reading the rest of the stream.
+///
+/// `r` is sniffed for the gzip magic bytes first and transparently decompressed if present, the
+/// same as [parse_from_buffer](parse_from_buffer).
+///
+/// This trades the reference table and body (always `None`, see
+/// [parse_from_buffer](parse_from_buffer) if you need those) for the ability to parse large
+/// replay/map files straight off an `HTTP` body, a zip entry, or anywhere else a full in-memory
+/// buffer would be wasteful.
+pub fn parse_from_reader<R: Read>(mut r: R, options: ParseOptions) -> Result<GBX, ParseError> {
+    let mut peek = [0u8; 2];
+    r.read_exact(&mut peek).map_err(ParseError::IOError)?;
+
+    let mut r: Box<dyn Read> = if peek == GZIP_MAGIC {
+        Box::new(GzDecoder::new(Cursor::new(peek).chain(r)))
+    } else {
+        Box::new(Cursor::new(peek).chain(r))
+    };
+
+    let mut buffer = vec![0u8; 13];
+    r.read_exact(&mut buffer).map_err(ParseError::IOError)?;
+
+    let binary_header = parse_binary_header(&buffer)?;
+
+    let hs = read_until_window(&mut r, HEADER_START_TOKEN, &mut buffer)?;
+    let he = read_until_window(&mut r, HEADER_END_TOKEN, &mut buffer)? + HEADER_END_TOKEN.len();
+
+    let header_xml =
+        String::from_utf8(buffer[hs..he].to_vec()).map_err(|_| ParseError::InvalidUtf8)?;
+    let header = parse_header_xml(&buffer[hs..he], options).ok();
+    let xml_chunk_size_offset = locate_xml_chunk_size_offset(&buffer, hs);
+
+    Ok(GBX {
+        origin: GBXOrigin::Buffer,
+        // Only the bytes needed to reach `</header>` were ever read; this is not the true file
+        // size.
+        filesize: buffer.len(),
+        header_length: he - hs,
+        header_start: hs,
+        header,
+        header_xml,
+        bin_header: binary_header,
+        reference_table: None,
+        body: None,
+        raw_prefix: buffer[..hs].to_vec(),
+        xml_chunk_size_offset,
+        // The stream was never read past `</header>`, so there is nothing to carry through here.
+        raw_suffix: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn read_until_window_finds_a_needle_past_the_first_chunk_boundary() {
+        // The needle sits well beyond a single 4096-byte read, so finding it requires scanning
+        // bytes appended by more than one `read_until_window` iteration.
+        let mut src = vec![b'x'; 5000];
+        src.extend_from_slice(b"<header ");
+        let mut buffer = Vec::new();
+
+        let pos = read_until_window(&mut Cursor::new(src.clone()), b"<header ", &mut buffer)
+            .expect("needle spans a later chunk and should still be found");
+
+        assert_eq!(pos, 5000);
+    }
+
+    #[test]
+    fn parse_from_reader_finds_a_header_past_the_first_chunk_boundary() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(b"GBX");
+        buf.extend_from_slice(&6u16.to_le_bytes());
+        buf.push(b'B');
+        buf.push(b'U');
+        buf.push(b'U');
+        buf.push(0);
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        // Padding well past the 4096-byte read chunk size used by `read_until_window`.
+        buf.extend_from_slice(&vec![b'p'; 5000]);
+        buf.extend_from_slice(b"<header type='replay'></header>");
+
+        let gbx = parse_from_reader(Cursor::new(buf), ParseOptions::default())
+            .expect("header past the first read chunk should still be found");
+
+        assert!(matches!(gbx.header, Some(GBXHeader::Replay(_))));
+    }
+
+    #[test]
+    fn parse_from_buffer_transparently_decompresses_a_gzipped_file() {
+        use std::io::Write;
+
+        let mut raw = Vec::new();
+        raw.extend_from_slice(b"GBX");
+        raw.extend_from_slice(&6u16.to_le_bytes());
+        raw.push(b'B');
+        raw.push(b'U');
+        raw.push(b'U');
+        raw.push(0);
+        raw.extend_from_slice(&0u32.to_le_bytes());
+        raw.extend_from_slice(b"<header type='replay'></header>");
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&raw).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let gbx = parse_from_buffer(&gzipped, ParseOptions::default())
+            .expect("a gzip-wrapped GBX file should be sniffed and decompressed transparently");
+
+        assert!(matches!(gbx.header, Some(GBXHeader::Replay(_))));
+    }
+}
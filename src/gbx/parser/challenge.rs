@@ -1,19 +1,33 @@
 use std::convert::TryFrom;
+use std::num::ParseIntError;
 
 use xml::{reader::XmlEvent, EventReader};
 
 use crate::gbx::*;
 
-use super::ParseError;
+use super::{resolve_enum, ParseError, ParseOptions};
 
 /// Parses the xml included in GBX file for challenges
-pub(crate) fn parse_challenge_header_xml<'a>(buf: &[u8]) -> Result<ChallengeXMLHeader, ParseError> {
-    let xmlp = EventReader::new(buf);
+pub(crate) fn parse_challenge_header_xml(
+    buf: &[u8],
+    options: ParseOptions,
+) -> Result<ChallengeXMLHeader, ParseError> {
+    let mut xmlp = EventReader::new(buf);
 
     let mut header = ChallengeXMLHeader::default();
 
-    for e in xmlp {
-        match e {
+    loop {
+        let position = xmlp.position();
+        let value_error = |attribute: &str| {
+            move |source: ParseIntError| ParseError::HeaderValueError {
+                source,
+                attribute: attribute.to_owned(),
+                position,
+            }
+        };
+
+        match xmlp.next() {
+            Ok(XmlEvent::EndDocument) => break,
             Ok(XmlEvent::StartElement {
                 name, attributes, ..
             }) => match name.local_name.as_str() {
@@ -21,12 +35,26 @@ pub(crate) fn parse_challenge_header_xml<'a>(buf: &[u8]) -> Result<ChallengeXMLH
                     for attr in attributes {
                         match attr.name.local_name.as_str() {
                             "type" => {
-                                header.maptype = MapType::try_from(attr.value.as_str())
-                                    .map_err(|e| ParseError::HeaderTryIntoEnumError(e))?
+                                if let Some(v) = resolve_enum(
+                                    MapType::try_from(attr.value.as_str()),
+                                    options.resolve,
+                                    |_| MapType::default(),
+                                    "type",
+                                    position,
+                                )? {
+                                    header.maptype = v
+                                }
                             }
                             "version" => {
-                                header.mapversion = GBXVersion::try_from(attr.value.as_str())
-                                    .map_err(|e| ParseError::HeaderTryIntoEnumError(e))?
+                                if let Some(v) = resolve_enum(
+                                    GBXVersion::try_from(attr.value.as_str()),
+                                    options.resolve,
+                                    |_| GBXVersion::Unknown,
+                                    "version",
+                                    position,
+                                )? {
+                                    header.mapversion = v
+                                }
                             }
                             "exever" => header.exever = String::from(attr.value),
                             _ => (),
@@ -39,37 +67,76 @@ pub(crate) fn parse_challenge_header_xml<'a>(buf: &[u8]) -> Result<ChallengeXMLH
                             "uid" => header.uid = attr.value,
                             "name" => header.name = attr.value,
                             "author" => header.author = attr.value,
+                            "authorzone" => header.authorzone = Some(attr.value),
                             _ => (),
                         }
                     }
                 }
+                "playermodel" => {
+                    let mut playermodel = PlayerModel::default();
+                    for attr in attributes {
+                        match attr.name.local_name.as_str() {
+                            "id" => playermodel.id = attr.value,
+                            "name" => playermodel.name = attr.value,
+                            "collection" => playermodel.collection = attr.value,
+                            _ => (),
+                        }
+                    }
+                    header.playermodel = Some(playermodel);
+                }
                 "desc" => {
                     for attr in attributes {
                         match attr.name.local_name.as_str() {
                             "envir" => {
-                                header.envir = Environment::try_from(attr.value.as_str())
-                                    .map_err(|e| ParseError::HeaderTryIntoEnumError(e))?
+                                if let Some(v) = resolve_enum(
+                                    Environment::try_from(attr.value.as_str()),
+                                    options.resolve,
+                                    Environment::Other,
+                                    "envir",
+                                    position,
+                                )? {
+                                    header.envir = v
+                                }
                             }
                             "mood" => {
-                                header.mood = Mood::try_from(attr.value.as_str())
-                                    .map_err(|e| ParseError::HeaderTryIntoEnumError(e))?
+                                if let Some(v) = resolve_enum(
+                                    Mood::try_from(attr.value.as_str()),
+                                    options.resolve,
+                                    |_| Mood::default(),
+                                    "mood",
+                                    position,
+                                )? {
+                                    header.mood = v
+                                }
                             }
                             "type" => {
-                                header.desctype = DescType::try_from(attr.value.as_str())
-                                    .map_err(|e| ParseError::HeaderTryIntoEnumError(e))?
+                                if let Some(v) = resolve_enum(
+                                    DescType::try_from(attr.value.as_str()),
+                                    options.resolve,
+                                    |_| DescType::default(),
+                                    "type",
+                                    position,
+                                )? {
+                                    header.desctype = v
+                                }
                             }
                             "nblaps" => {
-                                header.nblaps = attr
-                                    .value
-                                    .parse()
-                                    .map_err(|p| ParseError::HeaderValueError(p))?
+                                header.nblaps = attr.value.parse().map_err(value_error("nblaps"))?
                             }
                             "price" => {
-                                header.price = attr
-                                    .value
-                                    .parse()
-                                    .map_err(|p| ParseError::HeaderValueError(p))?
+                                header.price = attr.value.parse().map_err(value_error("price"))?
+                            }
+                            "nbcheckpoints" => {
+                                header.nbcheckpoints = Some(
+                                    attr.value.parse().map_err(value_error("nbcheckpoints"))?,
+                                )
+                            }
+                            "displaycost" => {
+                                header.displaycost = Some(
+                                    attr.value.parse().map_err(value_error("displaycost"))?,
+                                )
                             }
+                            "mod" => header.map_mod = Some(attr.value),
                             _ => (),
                         }
                     }
@@ -133,13 +200,58 @@ pub(crate) fn parse_challenge_header_xml<'a>(buf: &[u8]) -> Result<ChallengeXMLH
                 }
                 _ => (),
             },
-            Err(e) => {
-                println!("error {}", e);
-                break;
-            }
-            _ => {}
+            Ok(_) => (),
+            Err(e) => return Err(ParseError::XMLParseError(e)),
         }
     }
 
     Ok(header)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUF_WITH_UNKNOWN_ENVIR: &[u8] =
+        b"<header type=\"Challenge\"><desc envir=\"Bay\" mood=\"Day\" type=\"Race\"/></header>";
+
+    fn options_with(resolve: FailedResolveStrategy) -> ParseOptions {
+        ParseOptions { resolve }
+    }
+
+    #[test]
+    fn error_strategy_fails_the_parse_on_an_unknown_environment() {
+        let err = parse_challenge_header_xml(
+            BUF_WITH_UNKNOWN_ENVIR,
+            options_with(FailedResolveStrategy::Error),
+        )
+        .expect_err("an unknown envir should fail to resolve under the Error strategy");
+
+        assert!(matches!(
+            err,
+            ParseError::HeaderTryIntoEnumError { ref attribute, .. } if attribute == "envir"
+        ));
+    }
+
+    #[test]
+    fn ignore_strategy_skips_an_unknown_environment_and_keeps_the_default() {
+        let header = parse_challenge_header_xml(
+            BUF_WITH_UNKNOWN_ENVIR,
+            options_with(FailedResolveStrategy::Ignore),
+        )
+        .expect("the Ignore strategy should skip the unresolved attribute instead of failing");
+
+        assert_eq!(header.envir, Environment::default());
+    }
+
+    #[test]
+    fn stub_strategy_captures_an_unknown_environment_as_other() {
+        let header = parse_challenge_header_xml(
+            BUF_WITH_UNKNOWN_ENVIR,
+            options_with(FailedResolveStrategy::Stub),
+        )
+        .expect("the Stub strategy should record the raw value instead of failing");
+
+        assert_eq!(header.envir, Environment::Other("Bay".to_owned()));
+    }
+}
@@ -0,0 +1,121 @@
+//! Event-driven ("SAX-style") walk of a GBX replay `<header>` block.
+//!
+//! [parse_replay_xml](super::replay::parse_replay_xml) always materializes a whole
+//! [ReplayXMLHeader](crate::gbx::ReplayXMLHeader), even when a caller only wants, say, the map
+//! UID. [parse_header_events] exposes the same walk as a callback instead, so callers pick out
+//! what they need without allocating a struct per file — the same start-element/attribute
+//! callback pattern librsvg uses over libxml2. The walk always runs to the end of the document;
+//! the callback cannot stop it early.
+
+use std::io::Read;
+use std::num::ParseIntError;
+use std::{convert::TryFrom, str::FromStr};
+
+use xml::{reader::XmlEvent, EventReader};
+
+use crate::gbx::GBXVersion;
+
+use super::{resolve_enum, ParseError, ParseOptions};
+
+/// One fact read off a replay `<header>` block, emitted as soon as its XML attribute is seen.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderEvent<'a> {
+    /// The `<header type="...">` attribute, before it's known to name a replay at all.
+    HeaderType(&'a str),
+    Version(GBXVersion),
+    ExeVer(&'a str),
+    MapUid(&'a str),
+    MapName(&'a str),
+    BestTime(u32),
+    Respawns(i32),
+    StuntScore(u32),
+    Validable(bool),
+}
+
+/// Walks `src`'s replay `<header>` element, calling `f` with a [HeaderEvent] for each attribute
+/// recognized along the way.
+///
+/// This is the same walk [parse_replay_xml](super::replay::parse_replay_xml) does, just without
+/// accumulating the result into a [ReplayXMLHeader](crate::gbx::ReplayXMLHeader). `f` has no way
+/// to stop the walk: the whole document is read and every recognized attribute is reported, even
+/// after `f` has seen what it needs.
+pub fn parse_header_events<R: Read, F: FnMut(HeaderEvent)>(
+    src: R,
+    options: ParseOptions,
+    f: &mut F,
+) -> Result<(), ParseError> {
+    let mut xmlp = EventReader::new(src);
+
+    loop {
+        let position = xmlp.position();
+        let value_error = |attribute: &str| {
+            move |source: ParseIntError| ParseError::HeaderValueError {
+                source,
+                attribute: attribute.to_owned(),
+                position,
+            }
+        };
+
+        match xmlp.next() {
+            Ok(XmlEvent::EndDocument) => return Ok(()),
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) => match name.local_name.as_str() {
+                "header" => {
+                    for attr in attributes {
+                        match attr.name.local_name.as_str() {
+                            "type" => f(HeaderEvent::HeaderType(&attr.value)),
+                            "version" => {
+                                if let Some(v) = resolve_enum(
+                                    GBXVersion::try_from(attr.value.as_ref()),
+                                    options.resolve,
+                                    |_| GBXVersion::Unknown,
+                                    "version",
+                                    position,
+                                )? {
+                                    f(HeaderEvent::Version(v));
+                                }
+                            }
+                            "exever" => f(HeaderEvent::ExeVer(&attr.value)),
+                            _ => (),
+                        }
+                    }
+                }
+                "map" => {
+                    for attr in attributes {
+                        match attr.name.local_name.as_str() {
+                            "uid" => f(HeaderEvent::MapUid(&attr.value)),
+                            "name" => f(HeaderEvent::MapName(&attr.value)),
+                            _ => (),
+                        }
+                    }
+                }
+                "times" => {
+                    for attr in attributes {
+                        match attr.name.local_name.as_str() {
+                            "best" => f(HeaderEvent::BestTime(
+                                u32::from_str(attr.value.as_str()).map_err(value_error("best"))?,
+                            )),
+                            "respawns" => f(HeaderEvent::Respawns(
+                                i32::from_str(attr.value.as_str())
+                                    .map_err(value_error("respawns"))?,
+                            )),
+                            "stuntscore" => f(HeaderEvent::StuntScore(
+                                u32::from_str(attr.value.as_str())
+                                    .map_err(value_error("stuntscore"))?,
+                            )),
+                            "validable" => f(HeaderEvent::Validable(
+                                0 != u32::from_str(attr.value.as_str())
+                                    .map_err(value_error("validable"))?,
+                            )),
+                            _ => (),
+                        }
+                    }
+                }
+                _ => (),
+            },
+            Ok(_) => (),
+            Err(e) => return Err(ParseError::XMLParseError(e)),
+        }
+    }
+}
@@ -1,80 +1,35 @@
-use std::{convert::TryFrom, str::FromStr};
+use std::io::Read;
 
-use xml::{reader::XmlEvent, EventReader};
+use crate::gbx::ReplayXMLHeader;
 
-use crate::gbx::{GBXVersion, ReplayXMLHeader};
-
-use super::ParseError;
-
-/// Parses the xml included in GBX replay
-pub(crate) fn parse_replay_xml(buf: &[u8]) -> Result<ReplayXMLHeader, ParseError> {
-    let xmlp = EventReader::new(buf);
+use super::events::{parse_header_events, HeaderEvent};
+use super::{ParseError, ParseOptions};
 
+/// Parses the xml included in GBX replay.
+///
+/// Accepts anything [Read], so callers can hand this a `File` or a decompressor directly instead
+/// of buffering the whole header into a `Vec<u8>` first.
+///
+/// Built on top of [parse_header_events]; callers who only need one or two fields (e.g. just the
+/// map UID) should use that directly instead of paying for this whole struct.
+pub(crate) fn parse_replay_xml<R: Read>(
+    src: R,
+    options: ParseOptions,
+) -> Result<ReplayXMLHeader, ParseError> {
     let mut header = ReplayXMLHeader::default();
     let mut is_replay = false;
 
-    for e in xmlp {
-        match e {
-            Ok(XmlEvent::StartElement {
-                name, attributes, ..
-            }) => match name.local_name.as_str() {
-                "header" => {
-                    for attr in attributes {
-                        match attr.name.local_name.as_str() {
-                            "type" => match attr.value.as_ref() {
-                                "replay" => is_replay = true,
-                                _ => continue,
-                            },
-                            "version" => {
-                                header.version = GBXVersion::try_from(attr.value.as_ref())
-                                    .map_err(ParseError::HeaderTryIntoEnumError)?
-                            }
-                            "exever" => {
-                                header.exever = attr.value;
-                            }
-                            _ => (),
-                        }
-                    }
-                }
-                "map" => {
-                    for attr in attributes {
-                        match attr.name.local_name.as_str() {
-                            "uid" => header.map_uid = attr.value,
-                            "name" => header.map_name = attr.value,
-                            _ => (),
-                        }
-                    }
-                }
-                "times" => {
-                    for attr in attributes {
-                        match attr.name.local_name.as_str() {
-                            "best" => {
-                                header.score.best = u32::from_str(attr.value.as_str())
-                                    .map_err(ParseError::HeaderValueError)?
-                            }
-                            "respawns" => {
-                                header.score.respawns = i32::from_str(attr.value.as_str())
-                                    .map_err(ParseError::HeaderValueError)?
-                            }
-                            "stuntscore" => {
-                                header.score.stuntscore = u32::from_str(attr.value.as_str())
-                                    .map_err(ParseError::HeaderValueError)?
-                            }
-                            "validable" => {
-                                header.score.validable = 0
-                                    != u32::from_str(attr.value.as_str())
-                                        .map_err(ParseError::HeaderValueError)?
-                            }
-                            _ => (),
-                        }
-                    }
-                }
-                _ => (),
-            },
-            Err(e) => return Err(ParseError::XMLParseError(e)),
-            _ => {}
-        }
-    }
+    parse_header_events(src, options, &mut |event| match event {
+        HeaderEvent::HeaderType(t) => is_replay = t == "replay",
+        HeaderEvent::Version(v) => header.version = v,
+        HeaderEvent::ExeVer(v) => header.exever = v.to_owned(),
+        HeaderEvent::MapUid(v) => header.map_uid = v.to_owned(),
+        HeaderEvent::MapName(v) => header.map_name = v.to_owned(),
+        HeaderEvent::BestTime(v) => header.score.best = v,
+        HeaderEvent::Respawns(v) => header.score.respawns = v,
+        HeaderEvent::StuntScore(v) => header.score.stuntscore = v,
+        HeaderEvent::Validable(v) => header.score.validable = v,
+    })?;
 
     if is_replay {
         Ok(header)
@@ -86,7 +41,10 @@ pub(crate) fn parse_replay_xml(buf: &[u8]) -> Result<ReplayXMLHeader, ParseError
 #[cfg(test)]
 mod tests {
 
-    use crate::gbx::{parser::ParseError, ReplayXMLHeader};
+    use crate::gbx::{
+        parser::{ParseError, ParseOptions},
+        ReplayXMLHeader,
+    };
 
     use super::parse_replay_xml;
 
@@ -98,7 +56,10 @@ mod tests {
         )];
 
         for p in pairs {
-            match (parse_replay_xml(p.0), p.1.as_ref()) {
+            match (
+                parse_replay_xml(p.0, ParseOptions::default()),
+                p.1.as_ref(),
+            ) {
                 (Ok(h), Some(t)) => {
                     assert_eq!(&h, t);
                 }
@@ -110,8 +71,8 @@ mod tests {
 
     #[test]
     fn unuccessfull_parse() {
-        if let ParseError::XMLParseError(xml_error) =
-            parse_replay_xml(b"").expect_err("Expecting xml lib to fail on empty buffer")
+        if let ParseError::XMLParseError(xml_error) = parse_replay_xml(b"", ParseOptions::default())
+            .expect_err("Expecting xml lib to fail on empty buffer")
         {
             // If pair.1 == None any Error is accepted
             let pairs: &[(&'static [u8], Option<ParseError>)] = &[
@@ -124,7 +85,7 @@ mod tests {
             ];
 
             for p in pairs {
-                match parse_replay_xml(p.0) {
+                match parse_replay_xml(p.0, ParseOptions::default()) {
                     Err(e) => {
                         if let Some(exp) = &p.1 {
                             assert_eq!(
@@ -0,0 +1,340 @@
+//! Serializes a [GBX] back into bytes.
+//!
+//! Only the header is regenerated, from the typed fields on [GBX::header]; everything else (the
+//! binary prefix and whatever followed the original header) is carried through, with only the
+//! two size fields in the prefix that cover the header patched to its new length. This
+//! mirrors the `Serializable::read_from`/`write_to` split other binary-format crates use for
+//! round-trip editing, and is enough to edit map metadata (author, name, medal times, mood) and
+//! write a valid file back out.
+
+use crate::gbx::{
+    ChallengeXMLHeader, DescType, Environment, GBXHeader, GBXVersion, MapType, Mood, PlayerModel,
+    ReplayXMLHeader, Times, GBX,
+};
+
+/// Serializes `gbx` back into a full GBX file: its original binary prefix, a freshly generated
+/// `<header>` element built from `gbx.header` (nothing at all if it is `None`), and whatever bytes
+/// followed the original header.
+///
+/// Edit the typed fields on [GBX::header] before calling this. The text of [GBX::header_xml] is
+/// not written out; only its byte length is used, as the length of the header being replaced, so
+/// editing it throws off the size patching below.
+///
+/// If the regenerated XML is a different byte length than the original, `userDataSize` and the
+/// XML chunk's entry in the header-chunk descriptor table (both inside the original binary
+/// prefix) are patched to match, so the result stays a well-formed GBX file instead of one whose
+/// declared chunk/section sizes no longer match their contents.
+pub fn write_to_buffer(gbx: &GBX) -> Vec<u8> {
+    let xml = match gbx.header.as_ref() {
+        Some(GBXHeader::Replay(header)) => write_replay_header_xml(header),
+        Some(GBXHeader::Challenge(header)) => write_challenge_header_xml(header),
+        None => String::new(),
+    };
+
+    let mut raw_prefix = gbx.raw_prefix.clone();
+    let size_delta = xml.len() as i64 - gbx.header_xml.len() as i64;
+    if size_delta != 0 {
+        patch_u32_le(&mut raw_prefix, super::BINARY_HEADER_LEN, |user_data_size| {
+            (user_data_size as i64 + size_delta) as u32
+        });
+
+        if let Some(offset) = gbx.xml_chunk_size_offset {
+            patch_u32_le(&mut raw_prefix, offset, |size_raw| {
+                let flag = size_raw & super::HEAVY_CHUNK_FLAG;
+                let size = size_raw & !super::HEAVY_CHUNK_FLAG;
+                (((size as i64 + size_delta) as u32) & !super::HEAVY_CHUNK_FLAG) | flag
+            });
+        }
+    }
+
+    let mut out = Vec::with_capacity(raw_prefix.len() + xml.len() + gbx.raw_suffix.len());
+    out.extend_from_slice(&raw_prefix);
+    out.extend_from_slice(xml.as_bytes());
+    out.extend_from_slice(&gbx.raw_suffix);
+    out
+}
+
+/// Replaces the little-endian `u32` at `offset` with the result of applying `f` to its current
+/// value. Does nothing if `offset` doesn't point at four in-bounds bytes, the same best-effort
+/// spirit as the rest of this crate's binary-layout handling.
+fn patch_u32_le(buf: &mut [u8], offset: usize, f: impl FnOnce(u32) -> u32) {
+    if let Some(bytes) = buf.get_mut(offset..offset + 4) {
+        let current = u32::from_le_bytes(bytes.try_into().unwrap());
+        bytes.copy_from_slice(&f(current).to_le_bytes());
+    }
+}
+
+fn version_attr(version: &GBXVersion) -> &'static str {
+    match version {
+        GBXVersion::TMc6 => "TMc.6",
+        GBXVersion::TMr7 | GBXVersion::Unknown => "TMr.7",
+    }
+}
+
+fn maptype_attr(maptype: &MapType) -> &'static str {
+    match maptype {
+        MapType::Challenge => "Challenge",
+    }
+}
+
+fn environment_attr(envir: &Environment) -> &str {
+    match envir {
+        Environment::Stadium => "Stadium",
+        Environment::Other(raw) => raw,
+    }
+}
+
+fn mood_attr(mood: &Mood) -> &'static str {
+    match mood {
+        Mood::Day => "Day",
+        Mood::Sunset => "Sunset",
+        Mood::Sunrise => "Sunrise",
+        Mood::Night => "Night",
+    }
+}
+
+fn desctype_attr(desctype: &DescType) -> &'static str {
+    match desctype {
+        DescType::Race => "Race",
+    }
+}
+
+fn time_attr(time: Option<u32>) -> String {
+    time.map_or_else(|| "-1".to_owned(), |t| t.to_string())
+}
+
+fn write_replay_header_xml(header: &ReplayXMLHeader) -> String {
+    format!(
+        concat!(
+            "<header type=\"replay\" version=\"{}\" exever=\"{}\">",
+            "<map uid=\"{}\" name=\"{}\"/>",
+            "<times best=\"{}\" respawns=\"{}\" stuntscore=\"{}\" validable=\"{}\"/>",
+            "</header>"
+        ),
+        version_attr(&header.version),
+        header.exever,
+        header.map_uid,
+        header.map_name,
+        header.score.best,
+        header.score.respawns,
+        header.score.stuntscore,
+        header.score.validable as u8,
+    )
+}
+
+fn write_challenge_header_xml(header: &ChallengeXMLHeader) -> String {
+    let times = header.times.as_ref().map_or_else(String::new, |times| {
+        write_times_xml(times)
+    });
+    let deps = write_dependencies_xml(&header.dependencies);
+    let playermodel = write_playermodel_xml(header.playermodel.as_ref());
+    let authorzone = optional_str_attr("authorzone", &header.authorzone);
+    let nbcheckpoints = optional_u32_attr("nbcheckpoints", header.nbcheckpoints);
+    let displaycost = optional_u32_attr("displaycost", header.displaycost);
+    let map_mod = optional_str_attr("mod", &header.map_mod);
+
+    format!(
+        concat!(
+            "<header type=\"{}\" version=\"{}\" exever=\"{}\">",
+            "<ident uid=\"{}\" name=\"{}\" author=\"{}\"{}/>",
+            "<desc envir=\"{}\" mood=\"{}\" type=\"{}\" nblaps=\"{}\" price=\"{}\"{}{}{}/>",
+            "{}{}{}</header>"
+        ),
+        maptype_attr(&header.maptype),
+        version_attr(&header.mapversion),
+        header.exever,
+        header.uid,
+        header.name,
+        header.author,
+        authorzone,
+        environment_attr(&header.envir),
+        mood_attr(&header.mood),
+        desctype_attr(&header.desctype),
+        header.nblaps,
+        header.price,
+        nbcheckpoints,
+        displaycost,
+        map_mod,
+        playermodel,
+        times,
+        deps,
+    )
+}
+
+/// Formats `name="value"` (with a leading space) if `value` is present, or the empty string
+/// otherwise, for `<desc>`/`<ident>` attributes this crate doesn't interpret but must not drop on
+/// a round trip.
+fn optional_str_attr(name: &str, value: &Option<String>) -> String {
+    value
+        .as_deref()
+        .map_or_else(String::new, |v| format!(" {}=\"{}\"", name, v))
+}
+
+fn optional_u32_attr(name: &str, value: Option<u32>) -> String {
+    value.map_or_else(String::new, |v| format!(" {}=\"{}\"", name, v))
+}
+
+fn write_playermodel_xml(playermodel: Option<&PlayerModel>) -> String {
+    playermodel.map_or_else(String::new, |p| {
+        format!(
+            "<playermodel id=\"{}\" name=\"{}\" collection=\"{}\"/>",
+            p.id, p.name, p.collection
+        )
+    })
+}
+
+fn write_times_xml(times: &Times) -> String {
+    format!(
+        "<times bronze=\"{}\" silver=\"{}\" gold=\"{}\" authortime=\"{}\" authorscore=\"{}\"/>",
+        time_attr(times.bronze),
+        time_attr(times.silver),
+        time_attr(times.gold),
+        time_attr(times.authortime),
+        time_attr(times.authorscore),
+    )
+}
+
+fn write_dependencies_xml(dependencies: &[crate::gbx::Dependency]) -> String {
+    if dependencies.is_empty() {
+        return String::new();
+    }
+
+    let deps: String = dependencies
+        .iter()
+        .map(|dep| format!("<dep file=\"{}\"/>", dep.file))
+        .collect();
+    format!("<deps>{}</deps>", deps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gbx::parser::{parse_from_buffer, ParseOptions};
+
+    /// Builds a well-formed binary GBX buffer around a single replay-header chunk, so the
+    /// header-chunk table and `userDataSize` field line up the way a real file's would.
+    fn buffer_with_replay_header(xml: &str) -> Vec<u8> {
+        let mut user_data = Vec::new();
+        user_data.extend_from_slice(&1u32.to_le_bytes()); // num_header_chunks
+        user_data.extend_from_slice(&0x0304_3002u32.to_le_bytes()); // chunk_id
+        user_data.extend_from_slice(&(xml.len() as u32).to_le_bytes()); // chunk_size
+        user_data.extend_from_slice(xml.as_bytes());
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(b"GBX");
+        buf.extend_from_slice(&6u16.to_le_bytes());
+        buf.push(b'B');
+        buf.push(b'U');
+        buf.push(b'U');
+        buf.push(0);
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&(user_data.len() as u32).to_le_bytes()); // userDataSize
+        buf.extend_from_slice(&user_data);
+        buf.extend_from_slice(&0u32.to_le_bytes()); // num_nodes
+        buf.extend_from_slice(&0u32.to_le_bytes()); // num_external_nodes
+
+        buf
+    }
+
+    /// Builds a well-formed binary GBX buffer around a single challenge-header chunk.
+    fn buffer_with_challenge_header(xml: &str) -> Vec<u8> {
+        let mut user_data = Vec::new();
+        user_data.extend_from_slice(&1u32.to_le_bytes()); // num_header_chunks
+        user_data.extend_from_slice(&0x0304_3002u32.to_le_bytes()); // chunk_id
+        user_data.extend_from_slice(&(xml.len() as u32).to_le_bytes()); // chunk_size
+        user_data.extend_from_slice(xml.as_bytes());
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(b"GBX");
+        buf.extend_from_slice(&6u16.to_le_bytes());
+        buf.push(b'B');
+        buf.push(b'U');
+        buf.push(b'U');
+        buf.push(0);
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&(user_data.len() as u32).to_le_bytes()); // userDataSize
+        buf.extend_from_slice(&user_data);
+        buf.extend_from_slice(&0u32.to_le_bytes()); // num_nodes
+        buf.extend_from_slice(&0u32.to_le_bytes()); // num_external_nodes
+
+        buf
+    }
+
+    #[test]
+    fn round_trips_attributes_this_crate_does_not_interpret() {
+        let xml = concat!(
+            "<header type=\"Challenge\" version=\"TMc.6\" exever=\"1.0\">",
+            "<ident uid=\"uid\" name=\"Name\" author=\"author\" authorzone=\"World\"/>",
+            "<desc envir=\"Stadium\" mood=\"Day\" type=\"Race\" nblaps=\"3\" price=\"100\" ",
+            "nbcheckpoints=\"7\" displaycost=\"200\" mod=\"CustomMod\"/>",
+            "<playermodel id=\"id\" name=\"Model\" collection=\"Stadium\"/>",
+            "</header>",
+        );
+        let buf = buffer_with_challenge_header(xml);
+
+        let mut gbx =
+            parse_from_buffer(&buf, ParseOptions::default()).expect("fixture should parse");
+
+        match gbx.header.as_mut() {
+            Some(GBXHeader::Challenge(header)) => {
+                assert_eq!(header.authorzone.as_deref(), Some("World"));
+                assert_eq!(header.nbcheckpoints, Some(7));
+                assert_eq!(header.displaycost, Some(200));
+                assert_eq!(header.map_mod.as_deref(), Some("CustomMod"));
+                assert_eq!(header.playermodel.as_ref().unwrap().name, "Model");
+                header.name = "A Much Longer Map Name Than Before".to_owned();
+            }
+            other => panic!("expected a challenge header, got {:?}", other),
+        }
+
+        let written = write_to_buffer(&gbx);
+
+        let reparsed = parse_from_buffer(&written, ParseOptions::default())
+            .expect("a GBX file with patched sizes should still parse");
+
+        match reparsed.header {
+            Some(GBXHeader::Challenge(header)) => {
+                assert_eq!(header.name, "A Much Longer Map Name Than Before");
+                assert_eq!(header.authorzone.as_deref(), Some("World"));
+                assert_eq!(header.nbcheckpoints, Some(7));
+                assert_eq!(header.displaycost, Some(200));
+                assert_eq!(header.map_mod.as_deref(), Some("CustomMod"));
+                assert_eq!(header.playermodel.unwrap().name, "Model");
+            }
+            other => panic!("expected a challenge header, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn round_trips_a_header_edit_that_changes_the_xml_length() {
+        let xml = concat!(
+            "<header type=\"replay\" version=\"TMr.7\" exever=\"1.0\">",
+            "<map uid=\"uid\" name=\"Short\"/>",
+            "<times best=\"0\" respawns=\"0\" stuntscore=\"0\" validable=\"0\"/>",
+            "</header>",
+        );
+        let buf = buffer_with_replay_header(xml);
+
+        let mut gbx =
+            parse_from_buffer(&buf, ParseOptions::default()).expect("fixture should parse");
+
+        match gbx.header.as_mut() {
+            Some(GBXHeader::Replay(header)) => {
+                header.map_name = "A Much Longer Map Name Than Before".to_owned();
+            }
+            other => panic!("expected a replay header, got {:?}", other),
+        }
+
+        let written = write_to_buffer(&gbx);
+
+        let reparsed = parse_from_buffer(&written, ParseOptions::default())
+            .expect("a GBX file with patched sizes should still parse");
+
+        match reparsed.header {
+            Some(GBXHeader::Replay(header)) => {
+                assert_eq!(header.map_name, "A Much Longer Map Name Than Before");
+            }
+            other => panic!("expected a replay header, got {:?}", other),
+        }
+    }
+}
@@ -2,4 +2,8 @@
 //! The datastructures used are found in [gbx](gbx).
 pub mod gbx;
 
-pub use gbx::parser::{parse_from_buffer, parse_from_file};
+pub use gbx::parser::{
+    parse_from_buffer, parse_from_file, parse_from_reader, FailedResolveStrategy, ParseOptions,
+};
+pub use gbx::parser::events::{parse_header_events, HeaderEvent};
+pub use gbx::parser::writer::write_to_buffer;